@@ -8,90 +8,301 @@ pub trait PriorityQueue<Element> {
     fn is_empty(&self) -> bool;
     /// returns the size of the queue.
     fn size(&self) -> usize;
-    /// returns the highest-priority element but does not modify the queue.
+    /// returns the highest-priority element but does not modify the queue
+    /// (the lowest-priority element if constructed with `Order::Ascending`).
     fn peek(&self) -> Option<Element>;
     /// add an element to the queue with an associated priority.
     fn insert(&mut self, element: Element, priority: u64);
-    /// remove the element from the queue that has the highest priority, and return it.
+    /// remove the element from the queue that has the highest priority, and
+    /// return it (the lowest-priority element if constructed with
+    /// `Order::Ascending`).
     fn pop(&mut self) -> Option<Element>;
+    /// update the priority of an already-queued element, re-ordering it.
+    ///
+    /// If the same bytes were inserted under several priorities, only the
+    /// first occurrence found in ascending-priority order is affected.
+    /// Returns whether a matching element was found.
+    fn change_priority(&mut self, element: &[u8], new_priority: u64) -> bool;
+    /// remove a specific element from the queue, regardless of its priority.
+    ///
+    /// If the same bytes were inserted under several priorities, only the
+    /// first occurrence found in ascending-priority order is removed.
+    /// Returns whether a matching element was found.
+    fn remove(&mut self, element: &[u8]) -> bool;
+}
+
+/// A key-value store ordered by key, used as the backing store for
+/// `PriorityQueueImpl`. Plugging in a persistent implementation (the byte
+/// layout is already serialization-friendly) lets the queue survive restarts.
+pub trait OrderedKvStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>);
+    fn remove(&mut self, key: &[u8]);
+    /// returns the entry with the lowest key, if any.
+    fn first(&self) -> Option<(Vec<u8>, Vec<u8>)>;
+    /// returns the entry with the highest key, if any.
+    fn last(&self) -> Option<(Vec<u8>, Vec<u8>)>;
+    /// iterates all entries in ascending key order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + '_>;
+    fn is_empty(&self) -> bool;
 }
 
 type KeyValueStore = BTreeMap<Vec<u8>, Vec<u8>>;
 
+impl OrderedKvStore for KeyValueStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        BTreeMap::get(self, key).cloned()
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        BTreeMap::insert(self, key, value);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        BTreeMap::remove(self, key);
+    }
+
+    fn first(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        BTreeMap::iter(self).next().map(|(k, v)| (k.clone(), v.clone()))
+    }
+
+    fn last(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        BTreeMap::iter(self).next_back().map(|(k, v)| (k.clone(), v.clone()))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + '_> {
+        Box::new(BTreeMap::iter(self).map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    fn is_empty(&self) -> bool {
+        BTreeMap::is_empty(self)
+    }
+}
+
+/// which end of the priority range `peek`/`pop` return from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// the lowest-priority element pops first (min-queue).
+    Ascending,
+    /// the highest-priority element pops first (max-queue). This is the default.
+    #[default]
+    Descending,
+}
+
 // Additional requirement: the underlying data structure needs to be a key-value stores
 // Note: you may simulate other data structure with key-value store
-pub struct PriorityQueueImpl(KeyValueStore);
-
-/// transform byte array: &[u8; 4] to u32
-fn as_u32_be(array: &[u8; 4]) -> u32 {
-    ((array[0] as u32) << 24) |
-    ((array[1] as u32) << 16) |
-    ((array[2] as u32) <<  8) |
-    ((array[3] as u32) <<  0)
+//
+// `index` is an auxiliary BTreeMap<element, priorities> kept in sync with every
+// insert/pop/change_priority so change_priority/remove can locate an element's
+// priority without scanning every bucket in `kv_store`.
+//
+// `capacity` bounds the element count for top-K style usage; `None` means unbounded.
+pub struct PriorityQueueImpl<S: OrderedKvStore = KeyValueStore>(S, BTreeMap<Vec<u8>, Vec<u64>>, Order, Option<usize>);
+
+/// transform byte array: &[u8; 8] to u64
+fn as_u64_be(array: &[u8; 8]) -> u64 {
+    let mut result: u64 = 0;
+    for byte in array.iter() {
+        result = (result << 8) | (*byte as u64);
+    }
+    result
 }
 
-const KEY_SIZE_BYTES: usize = 4;
-const ELEMENT_SIZE_BYTES: usize = 4;
+/// encode `value` as an unsigned LEB128 varint, appending it to `out`.
+///
+/// Each byte carries 7 bits of the value, low group first, with the high
+/// bit set on every byte but the last to signal that more bytes follow.
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
 
-/// returns next (element_size: &[u8], element: &[u8], next_slice: &[u8])
-fn next_element(slice: &[u8]) -> (&[u8], &[u8], &[u8]) {
-    let (element_size, o1) = slice.split_at(ELEMENT_SIZE_BYTES);
-    let element_usize = as_u32_be(element_size.try_into().unwrap()) as usize;
-    let (element, o2) = o1.split_at(element_usize);
-    return (element_size, element, o2);
+/// decode an unsigned LEB128 varint from the front of `slice`, returning the
+/// decoded value and the remaining, unconsumed slice.
+fn read_varint(slice: &[u8]) -> (u64, &[u8]) {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    let mut i = 0;
+    loop {
+        let byte = slice[i];
+        value |= ((byte & 0x7f) as u64) << shift;
+        i += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    (value, &slice[i..])
 }
 
-impl PriorityQueue<Vec<u8>> for PriorityQueueImpl {
+/// returns next (element: &[u8], next_slice: &[u8])
+fn next_element(slice: &[u8]) -> (&[u8], &[u8]) {
+    let (element_size, rest) = read_varint(slice);
+    rest.split_at(element_size as usize)
+}
 
-    fn new() -> Self {
-        PriorityQueueImpl(KeyValueStore::new())
+/// decode every element out of a priority bucket's blob, in storage
+/// (oldest-first) order.
+fn decode_bucket_elements(value: &[u8]) -> Vec<Vec<u8>> {
+    let (size, mut elements_slice) = read_varint(value);
+    let mut elements = Vec::with_capacity(size as usize);
+    for _ in 0..size {
+        let (element, next_slice) = next_element(elements_slice);
+        elements.push(element.to_vec());
+        elements_slice = next_slice;
     }
+    elements
+}
 
-    fn is_empty(&self) -> bool {
-        let PriorityQueueImpl(kv_store) = self;
-        kv_store.is_empty()
+impl<S: OrderedKvStore> PriorityQueueImpl<S> {
+    /// rebuild a priority queue from an already-populated `store` (e.g. one
+    /// reopened from disk), popping in the given `order` and bounded by the
+    /// given `capacity`.
+    ///
+    /// The `element -> priorities` index used by `change_priority`/`remove`
+    /// lives only in memory, so it is rebuilt here from `store`'s contents
+    /// rather than starting empty.
+    pub fn from_store(store: S, order: Order, capacity: Option<usize>) -> Self {
+        let mut index: BTreeMap<Vec<u8>, Vec<u64>> = BTreeMap::new();
+        for (key, value) in store.iter() {
+            let priority = as_u64_be(key.as_slice().try_into().unwrap());
+            for element in decode_bucket_elements(&value) {
+                index.entry(element).or_default().push(priority);
+            }
+        }
+        PriorityQueueImpl(store, index, order, capacity)
     }
+}
 
-    fn size(&self) -> usize {
-        let PriorityQueueImpl(kv_store) = self;
-        // iterate over everything and add size for every key
-        let mut size: usize = 0;
-        for (_, val) in kv_store.iter() {
-            let (key_size, _) = val.split_at(4);
-            size += as_u32_be(key_size.try_into().unwrap()) as usize;
-        }
-        size
+impl<S: OrderedKvStore + Default> PriorityQueueImpl<S> {
+    /// create a new priority queue that pops in the given `order` instead of
+    /// the default highest-priority-first behavior.
+    pub fn with_order(order: Order) -> Self {
+        PriorityQueueImpl(S::default(), BTreeMap::new(), order, None)
     }
 
-    fn peek(&self) -> Option<Vec<u8>> {
-        if self.is_empty() {
-            return None
-        }
+    /// create a new priority queue that holds at most `max` elements.
+    ///
+    /// Once at capacity, `insert` drops the current worst-priority element
+    /// to make room, or leaves the queue unchanged if the incoming priority
+    /// is not better than that worst. Use `insert_bounded` to find out
+    /// whether an element was evicted.
+    pub fn with_capacity(max: usize) -> Self {
+        PriorityQueueImpl(S::default(), BTreeMap::new(), Order::default(), Some(max))
+    }
 
-        let PriorityQueueImpl(kv_store) = self;
-        let (_, value) = kv_store.iter().next_back().unwrap();
-        let (key_size, mut elements_slice) = value.split_at(KEY_SIZE_BYTES);
-        let size = as_u32_be(key_size.try_into().unwrap());
+    /// create a new priority queue that pops in the given `order` and holds
+    /// at most `max` elements, combining `with_order` and `with_capacity`.
+    pub fn with_order_and_capacity(order: Order, max: usize) -> Self {
+        PriorityQueueImpl(S::default(), BTreeMap::new(), order, Some(max))
+    }
 
-        // loop n steps and return the last element
-        let mut n = size;
-        loop {
-            let (_, element, next_slice) = next_element(elements_slice);
-            elements_slice = next_slice;
+    /// like `insert`, but when the queue is already at capacity this evicts
+    /// the current worst-priority element to make room and returns it, or
+    /// returns `None` and leaves the queue unchanged if `priority` is not
+    /// better than that worst. A zero-capacity queue holds nothing and
+    /// returns the incoming `element` itself.
+    pub fn insert_bounded(&mut self, element: Vec<u8>, priority: u64) -> Option<Vec<u8>> {
+        self.insert_with_eviction(element, priority)
+    }
 
-            n -= 1;
-            if n == 0 {
-                return Some(element.to_vec());
+    fn insert_with_eviction(&mut self, element: Vec<u8>, priority: u64) -> Option<Vec<u8>> {
+        let max = self.3;
+        if let Some(max) = max {
+            if max == 0 {
+                // a zero-capacity queue never holds anything, so the
+                // incoming element is rejected outright.
+                return Some(element);
+            }
+
+            if self.size() >= max {
+                let (worst_priority, worst_element) = self.worst_priority_element()
+                    .expect("size() >= max > 0 implies at least one element");
+
+                let incoming_is_no_better = match self.2 {
+                    Order::Descending => priority <= worst_priority,
+                    Order::Ascending => priority >= worst_priority,
+                };
+                if incoming_is_no_better {
+                    return None;
+                }
+
+                self.remove_occurrence(worst_priority, &worst_element);
+                self.raw_insert(element, priority);
+                return Some(worst_element);
             }
         }
+
+        self.raw_insert(element, priority);
+        None
+    }
+
+    /// the worst-priority element currently queued for the configured pop
+    /// `Order` (the smallest key's oldest element when `Descending`, the
+    /// largest key's oldest element when `Ascending`) — i.e. the first one
+    /// `insert_with_eviction` should give up to make room.
+    fn worst_priority_element(&self) -> Option<(u64, Vec<u8>)> {
+        let PriorityQueueImpl(kv_store, _, order, _) = self;
+        let (key, value) = match order {
+            Order::Descending => kv_store.first(),
+            Order::Ascending => kv_store.last(),
+        }?;
+        let priority = as_u64_be(key.as_slice().try_into().unwrap());
+        let (_, elements_slice) = read_varint(&value);
+        let (element, _) = next_element(elements_slice);
+        Some((priority, element.to_vec()))
+    }
+
+    /// consume the queue, yielding every element paired with its priority in
+    /// pop order (respecting the configured `Order`). Within a single
+    /// priority, elements come out last-in-first-out, matching `pop`.
+    pub fn into_sorted_iter(self) -> impl Iterator<Item = (Vec<u8>, u64)> {
+        let PriorityQueueImpl(kv_store, _, order, _) = self;
+        let mut buckets: Vec<(Vec<u8>, Vec<u8>)> = kv_store.iter().collect();
+        if order == Order::Descending {
+            buckets.reverse();
+        }
+        buckets.into_iter().flat_map(|(key, value)| {
+            let priority = as_u64_be(key.as_slice().try_into().unwrap());
+            decode_bucket_elements(&value).into_iter().rev()
+                .map(move |element| (element, priority))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// like `into_sorted_iter`, but borrows the queue instead of consuming it.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, u64)> + '_ {
+        let PriorityQueueImpl(kv_store, _, order, _) = self;
+        let mut buckets: Vec<(Vec<u8>, Vec<u8>)> = kv_store.iter().collect();
+        if *order == Order::Descending {
+            buckets.reverse();
+        }
+        buckets.into_iter().flat_map(|(key, value)| {
+            let priority = as_u64_be(key.as_slice().try_into().unwrap());
+            decode_bucket_elements(&value).into_iter().rev()
+                .map(move |element| (element, priority))
+                .collect::<Vec<_>>()
+        })
     }
 
     /// We store elements in a K.V store where K is the priority.
     /// The underlying K.V store is implemented as BTreeMap that is ordered on keys (priority).
     /// As multiple elements can have the same priority we need to accommodate multiple values (elements) for same key (priority)
     ///
-    /// Elements are represented in bytes like this:
-    /// [elements_size: &[u8; 4], element_1_size: &[u8; 4], element_1 &[u8], element_2_size: &[u8; 4], element_2 &[u8], ...]
+    /// Elements are represented in bytes like this, with the element count and
+    /// each element's length written as LEB128 varints rather than a fixed
+    /// width, so small elements only cost 1 byte of framing and there is no
+    /// size ceiling:
+    /// [elements_size: varint, element_1_size: varint, element_1 &[u8], element_2_size: varint, element_2 &[u8], ...]
     ///
     /// For example:
     ///   queue.insert(vec![5], 10);
@@ -99,82 +310,207 @@ impl PriorityQueue<Vec<u8>> for PriorityQueueImpl {
     ///   queue.insert(vec![8,9,10], 10);
     ///
     /// Elements with priority 10 would be represented as following:
-    ///   [0,0,0,3,0,0,0,1,5,0,0,0,2,6,7,0,0,0,3,8,9,10]
-    /// ->| 3 ELM | u8; 1 |5| u8; 2 |6,7| u8; 3 |8,9,10]
+    ///   [3,1,5,2,6,7,3,8,9,10]
+    /// ->| 3 ELM | 1 |5| 2 |6,7| 3 |8,9,10]
     ///
-    fn insert(&mut self, element: Vec<u8>, priority: u64) {
-        // panic if element over max size (~2GB)
-        if element.len() > u32::max_value() as usize {
-            panic!("Element size {:?} greater than MAX: {:?}", element.len(), u32::max_value());
-        }
-
-        let PriorityQueueImpl(kv_store) = self;
+    fn raw_insert(&mut self, element: Vec<u8>, priority: u64) {
+        let PriorityQueueImpl(kv_store, index, ..) = self;
         let key = priority.to_be_bytes().to_vec();
-        let element_size = (element.len() as u32).to_be_bytes().to_vec();
+        let element_for_index = element.clone();
         // insert first element if store !contains key, or append to byte array
         let val =
-            if !kv_store.contains_key(&key) {
-                let key_size = vec![0,0,0,1];
-                key_size.into_iter()
-                    .chain(element_size.into_iter())
-                    .chain(element.into_iter())
-                    .collect()
-            } else {
-                let old_value: Vec<u8> = kv_store.get(&key).unwrap().to_vec();
-                let (old_key_size, other) = old_value.split_at(KEY_SIZE_BYTES);
-                let size = as_u32_be(old_key_size.try_into().unwrap());
-                let key_size = (size + 1).to_be_bytes().to_vec();
-                key_size.into_iter()
-                    .chain(other.to_vec().into_iter())
-                    .chain(element_size.into_iter())
-                    .chain(element.into_iter())
-                    .collect()
+            match kv_store.get(&key) {
+                None => {
+                    let mut val = Vec::new();
+                    write_varint(1, &mut val);
+                    write_varint(element.len() as u64, &mut val);
+                    val.extend_from_slice(&element);
+                    val
+                }
+                Some(old_value) => {
+                    let (size, other) = read_varint(&old_value);
+                    let mut val = Vec::new();
+                    write_varint(size + 1, &mut val);
+                    val.extend_from_slice(other);
+                    write_varint(element.len() as u64, &mut val);
+                    val.extend_from_slice(&element);
+                    val
+                }
             };
         kv_store.insert(key, val);
+        index.entry(element_for_index).or_default().push(priority);
     }
+}
 
-    fn pop(&mut self) -> Option<Vec<u8>> {
-        if self.is_empty() {
-            return None
+impl<S: OrderedKvStore + Default> PriorityQueue<Vec<u8>> for PriorityQueueImpl<S> {
+
+    fn new() -> Self {
+        PriorityQueueImpl(S::default(), BTreeMap::new(), Order::default(), None)
+    }
+
+    fn is_empty(&self) -> bool {
+        let PriorityQueueImpl(kv_store, ..) = self;
+        kv_store.is_empty()
+    }
+
+    fn size(&self) -> usize {
+        let PriorityQueueImpl(kv_store, ..) = self;
+        // iterate over everything and add size for every key
+        let mut size: usize = 0;
+        for (_, val) in kv_store.iter() {
+            let (count, _) = read_varint(&val);
+            size += count as usize;
         }
+        size
+    }
 
-        let PriorityQueueImpl(kv_store) = self;
-        let key: Vec<u8>;
-        let value: Vec<u8>;
-        {
-            // do not fight the borrow checker (immutable borrow short scope)
-            let (k, v) = kv_store.iter().next_back().unwrap();
-            key = k.to_vec();
-            value = v.to_vec();
+    fn peek(&self) -> Option<Vec<u8>> {
+        let PriorityQueueImpl(kv_store, _, order, ..) = self;
+        let (_, value) = match order {
+            Order::Descending => kv_store.last(),
+            Order::Ascending => kv_store.first(),
+        }?;
+        let (size, mut elements_slice) = read_varint(&value);
+
+        // loop n steps and return the last element
+        let mut n = size;
+        loop {
+            let (element, next_slice) = next_element(elements_slice);
+            elements_slice = next_slice;
+
+            n -= 1;
+            if n == 0 {
+                return Some(element.to_vec());
+            }
         }
+    }
+
+    fn insert(&mut self, element: Vec<u8>, priority: u64) {
+        self.insert_with_eviction(element, priority);
+    }
 
-        let (key_size, mut elements_slice) = value.split_at(KEY_SIZE_BYTES);
-        let size = as_u32_be(key_size.try_into().unwrap());
-        let mut new_val = (size - 1).to_be_bytes().to_vec();
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let PriorityQueueImpl(kv_store, index, order, ..) = self;
+        let (key, value) = match order {
+            Order::Descending => kv_store.last(),
+            Order::Ascending => kv_store.first(),
+        }?;
+        let priority = as_u64_be(key.as_slice().try_into().unwrap());
+
+        let (size, mut elements_slice) = read_varint(&value);
+        let mut new_val = Vec::new();
+        write_varint(size - 1, &mut new_val);
 
         // loop n steps and return the last element
         // remove key if size == 1, else remove last element and update key
         let mut n = size;
         loop {
-            let (element_size, element, next_slice) = next_element(elements_slice);
+            let (element, next_slice) = next_element(elements_slice);
             elements_slice = next_slice;
 
             n -= 1;
             if n == 0 {
                 if size == 1 {
-                    kv_store.remove(&key.to_vec());
+                    kv_store.remove(&key);
                 } else {
                     kv_store.insert(key, new_val);
                 }
+                remove_from_index(index, element, priority);
                 return Some(element.to_vec());
             } else {
-                new_val = new_val.into_iter()
-                    .chain(element_size.to_vec().into_iter())
-                    .chain(element.to_vec().into_iter())
-                    .collect();
+                write_varint(element.len() as u64, &mut new_val);
+                new_val.extend_from_slice(element);
             }
         }
     }
+
+    fn change_priority(&mut self, element: &[u8], new_priority: u64) -> bool {
+        if !self.remove(element) {
+            return false;
+        }
+        self.insert(element.to_vec(), new_priority);
+        true
+    }
+
+    fn remove(&mut self, element: &[u8]) -> bool {
+        // the first occurrence in ascending-priority order is the lowest
+        // priority this element currently lives under
+        let priority = match self.1.get(element).and_then(|priorities| priorities.iter().min()) {
+            Some(priority) => *priority,
+            None => return false,
+        };
+
+        self.remove_occurrence(priority, element)
+    }
+}
+
+impl<S: OrderedKvStore> PriorityQueueImpl<S> {
+    /// remove the single occurrence of `element` filed under exactly
+    /// `priority`, leaving any other priorities it's queued under untouched.
+    /// Returns whether `element` was actually found there.
+    fn remove_occurrence(&mut self, priority: u64, element: &[u8]) -> bool {
+        let PriorityQueueImpl(kv_store, index, ..) = self;
+
+        let key = priority.to_be_bytes().to_vec();
+        let value = kv_store.get(&key).unwrap();
+        let (size, mut elements_slice) = read_varint(&value);
+
+        let mut kept: u64 = 0;
+        let mut remaining: Vec<u8> = Vec::new();
+        let mut found = false;
+
+        let mut n = size;
+        loop {
+            let (elem, next_slice) = next_element(elements_slice);
+            elements_slice = next_slice;
+            n -= 1;
+
+            if !found && elem == element {
+                found = true;
+            } else {
+                kept += 1;
+                write_varint(elem.len() as u64, &mut remaining);
+                remaining.extend_from_slice(elem);
+            }
+
+            if n == 0 {
+                break;
+            }
+        }
+
+        if !found {
+            return false;
+        }
+
+        if kept == 0 {
+            kv_store.remove(&key);
+        } else {
+            let mut new_val = Vec::new();
+            write_varint(kept, &mut new_val);
+            new_val.extend_from_slice(&remaining);
+            kv_store.insert(key, new_val);
+        }
+
+        remove_from_index(index, element, priority);
+        true
+    }
+}
+
+/// drop one occurrence of `priority` from `element`'s entry in the index,
+/// removing the entry entirely once it no longer lives under any priority.
+fn remove_from_index(index: &mut BTreeMap<Vec<u8>, Vec<u64>>, element: &[u8], priority: u64) {
+    let now_empty = match index.get_mut(element) {
+        Some(priorities) => {
+            if let Some(pos) = priorities.iter().position(|p| *p == priority) {
+                priorities.remove(pos);
+            }
+            priorities.is_empty()
+        }
+        None => false,
+    };
+    if now_empty {
+        index.remove(element);
+    }
 }
 
 #[cfg(test)]
@@ -182,14 +518,52 @@ mod tests {
     use super::*;
 
     #[test]
-    fn check_as_u32_be() {
-        assert_eq!(as_u32_be(&[0,0,0,1]), 1);
-        assert_eq!(as_u32_be(&[0xf0, 0x9f, 0x8f, 0xb3]), 4036988851);
+    fn check_varint_round_trip_boundary_lengths() {
+        let mut zero = Vec::new();
+        write_varint(0, &mut zero);
+        assert_eq!(zero, vec![0]);
+        assert_eq!(read_varint(&zero), (0, &[][..]));
+
+        let mut single_byte_max = Vec::new();
+        write_varint(127, &mut single_byte_max);
+        assert_eq!(single_byte_max, vec![0x7f]);
+        assert_eq!(read_varint(&single_byte_max), (127, &[][..]));
+
+        let mut two_byte_min = Vec::new();
+        write_varint(128, &mut two_byte_min);
+        assert_eq!(two_byte_min, vec![0x80, 0x01]);
+        assert_eq!(read_varint(&two_byte_min), (128, &[][..]));
+
+        let mut three_byte_min = Vec::new();
+        write_varint(16384, &mut three_byte_min);
+        assert_eq!(three_byte_min, vec![0x80, 0x80, 0x01]);
+        assert_eq!(read_varint(&three_byte_min), (16384, &[][..]));
+    }
+
+    #[test]
+    fn check_insert_pop_with_boundary_length_elements() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+
+        let empty = vec![];
+        let single_byte_max = vec![1u8; 127];
+        let two_byte_min = vec![2u8; 128];
+        let three_byte_min = vec![3u8; 16384];
+
+        queue.insert(empty.clone(), 1);
+        queue.insert(single_byte_max.clone(), 2);
+        queue.insert(two_byte_min.clone(), 3);
+        queue.insert(three_byte_min.clone(), 4);
+
+        assert_eq!(queue.pop(), Some(three_byte_min));
+        assert_eq!(queue.pop(), Some(two_byte_min));
+        assert_eq!(queue.pop(), Some(single_byte_max));
+        assert_eq!(queue.pop(), Some(empty));
+        assert!(queue.is_empty());
     }
 
     #[test]
     fn check_is_empty() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 5);
@@ -198,14 +572,14 @@ mod tests {
 
     #[test]
     fn check_size_empty() {
-        let queue = PriorityQueueImpl::new();
+        let queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
         assert_eq!(queue.size(), 0);
     }
 
     #[test]
     fn check_size() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
         assert_eq!(queue.size(), 0);
 
@@ -219,7 +593,7 @@ mod tests {
 
     #[test]
     fn check_insert() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 5);
@@ -228,7 +602,7 @@ mod tests {
 
     #[test]
     fn check_insert_many() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 10);
@@ -247,7 +621,7 @@ mod tests {
 
     #[test]
     fn check_insert_duplicate() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![4], 10);
@@ -267,14 +641,14 @@ mod tests {
 
     #[test]
     fn check_peek_empty() {
-        let queue = PriorityQueueImpl::new();
+        let queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
         assert_eq!(queue.peek(), None);
     }
 
     #[test]
     fn check_peek() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 5);
@@ -286,7 +660,7 @@ mod tests {
 
     #[test]
     fn check_pop_empty() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         assert_eq!(queue.size(), 0);
@@ -295,7 +669,7 @@ mod tests {
 
     #[test]
     fn check_pop() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 5);
@@ -307,7 +681,7 @@ mod tests {
 
     #[test]
     fn it_works() {
-        let mut queue = PriorityQueueImpl::new();
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue.is_empty());
 
         queue.insert(vec![0], 5);
@@ -330,8 +704,8 @@ mod tests {
 
     #[test]
     fn check_new_instances() {
-        let mut queue_first = PriorityQueueImpl::new();
-        let mut queue_second = PriorityQueueImpl::new();
+        let mut queue_first: PriorityQueueImpl = PriorityQueueImpl::new();
+        let mut queue_second: PriorityQueueImpl = PriorityQueueImpl::new();
         assert!(queue_first.is_empty());
         assert!(queue_second.is_empty());
 
@@ -347,4 +721,309 @@ mod tests {
         assert!(!queue_second.is_empty());
     }
 
+    #[test]
+    fn check_remove_missing() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        assert!(!queue.remove(&[0]));
+    }
+
+    #[test]
+    fn check_remove() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        assert!(queue.remove(&[1]));
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.peek(), Some(vec![0]));
+        assert!(!queue.remove(&[1]));
+    }
+
+    #[test]
+    fn check_remove_last_in_bucket() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 5);
+
+        assert!(queue.remove(&[0]));
+        assert_eq!(queue.size(), 1);
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_remove_first_occurrence_in_ascending_priority_order() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![9], 10);
+        queue.insert(vec![9], 3);
+
+        assert!(queue.remove(&[9]));
+        assert_eq!(queue.size(), 1);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![(vec![9], 10)]);
+        assert_eq!(queue.pop(), Some(vec![9]));
+        assert_eq!(queue.peek(), None);
+    }
+
+    #[test]
+    fn check_change_priority_missing() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        assert!(!queue.change_priority(&[0], 5));
+    }
+
+    #[test]
+    fn check_change_priority() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+
+        assert!(queue.change_priority(&[0], 20));
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.pop(), Some(vec![0]));
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[derive(Default)]
+    struct OtherStore(BTreeMap<Vec<u8>, Vec<u8>>);
+
+    impl OrderedKvStore for OtherStore {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            OrderedKvStore::get(&self.0, key)
+        }
+
+        fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+            OrderedKvStore::insert(&mut self.0, key, value);
+        }
+
+        fn remove(&mut self, key: &[u8]) {
+            OrderedKvStore::remove(&mut self.0, key);
+        }
+
+        fn first(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+            OrderedKvStore::first(&self.0)
+        }
+
+        fn last(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+            OrderedKvStore::last(&self.0)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + '_> {
+            OrderedKvStore::iter(&self.0)
+        }
+
+        fn is_empty(&self) -> bool {
+            OrderedKvStore::is_empty(&self.0)
+        }
+    }
+
+    #[test]
+    fn check_pluggable_store() {
+        let mut queue: PriorityQueueImpl<OtherStore> = PriorityQueueImpl::new();
+        assert!(queue.is_empty());
+
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        assert_eq!(queue.peek(), Some(vec![1]));
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert_eq!(queue.pop(), Some(vec![0]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_from_store_rebuilds_index_for_change_priority_and_remove() {
+        let mut store: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        let mut seed: PriorityQueueImpl = PriorityQueueImpl::new();
+        seed.insert(vec![0], 5);
+        seed.insert(vec![1], 10);
+        seed.insert(vec![2], 3);
+        let PriorityQueueImpl(seeded_store, ..) = seed;
+        for (key, value) in OrderedKvStore::iter(&seeded_store) {
+            store.insert(key, value);
+        }
+
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::from_store(store, Order::default(), None);
+        assert_eq!(queue.size(), 3);
+
+        assert!(queue.change_priority(&[0], 20));
+        assert_eq!(queue.pop(), Some(vec![0]));
+        assert!(queue.remove(&[2]));
+        assert_eq!(queue.size(), 1);
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_ascending_order() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_order(Order::Ascending);
+        assert!(queue.is_empty());
+
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        assert_eq!(queue.peek(), Some(vec![2]));
+        assert_eq!(queue.pop(), Some(vec![2]));
+        assert_eq!(queue.pop(), Some(vec![0]));
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_descending_order_is_default() {
+        let mut descending: PriorityQueueImpl = PriorityQueueImpl::with_order(Order::Descending);
+        let mut default: PriorityQueueImpl = PriorityQueueImpl::new();
+
+        descending.insert(vec![0], 5);
+        descending.insert(vec![1], 10);
+        default.insert(vec![0], 5);
+        default.insert(vec![1], 10);
+
+        assert_eq!(descending.pop(), default.pop());
+    }
+
+    #[test]
+    fn check_insert_bounded_under_capacity() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_capacity(2);
+
+        assert_eq!(queue.insert_bounded(vec![0], 5), None);
+        assert_eq!(queue.insert_bounded(vec![1], 10), None);
+        assert_eq!(queue.size(), 2);
+    }
+
+    #[test]
+    fn check_insert_bounded_evicts_lowest_priority() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_capacity(2);
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+
+        assert_eq!(queue.insert_bounded(vec![2], 20), Some(vec![0]));
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.pop(), Some(vec![2]));
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_insert_bounded_rejects_non_improving_priority() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_capacity(2);
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+
+        assert_eq!(queue.insert_bounded(vec![2], 5), None);
+        assert_eq!(queue.insert_bounded(vec![2], 3), None);
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert_eq!(queue.pop(), Some(vec![0]));
+    }
+
+    #[test]
+    fn check_insert_also_evicts_over_capacity() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_capacity(1);
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+
+        assert_eq!(queue.size(), 1);
+        assert_eq!(queue.pop(), Some(vec![1]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_ascending_capacity_evicts_highest_priority() {
+        let mut queue: PriorityQueueImpl =
+            PriorityQueueImpl::with_order_and_capacity(Order::Ascending, 2);
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+
+        // under an ascending (min-first) order the "worst" element to evict
+        // is the one with the highest priority, not the lowest.
+        assert_eq!(queue.insert_bounded(vec![2], 3), Some(vec![1]));
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.pop(), Some(vec![2]));
+        assert_eq!(queue.pop(), Some(vec![0]));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_ascending_capacity_evicts_worst_occurrence_of_a_duplicate_element() {
+        let mut queue: PriorityQueueImpl =
+            PriorityQueueImpl::with_order_and_capacity(Order::Ascending, 2);
+        queue.insert(vec![9], 3);
+        queue.insert(vec![9], 10);
+
+        // the worst occurrence under ascending order is `[9]@10`, so that's
+        // the one that must be evicted, leaving `[9]@3` still queued.
+        assert_eq!(queue.insert_bounded(vec![5], 1), Some(vec![9]));
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.pop(), Some(vec![5]));
+        assert_eq!(queue.pop(), Some(vec![9]));
+        assert_eq!(queue.peek(), None);
+    }
+
+    #[test]
+    fn check_zero_capacity_rejects_without_panicking() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_capacity(0);
+
+        assert_eq!(queue.insert_bounded(vec![0], 5), Some(vec![0]));
+        assert!(queue.is_empty());
+
+        queue.insert(vec![1], 10);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn check_iter() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        let pairs: Vec<(Vec<u8>, u64)> = queue.iter().collect();
+        assert_eq!(pairs, vec![(vec![1], 10), (vec![0], 5), (vec![2], 3)]);
+        // a non-consuming iter() does not empty the queue
+        assert_eq!(queue.size(), 3);
+    }
+
+    #[test]
+    fn check_iter_preserves_lifo_order_within_a_priority() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 5);
+
+        let pairs: Vec<(Vec<u8>, u64)> = queue.iter().collect();
+        assert_eq!(pairs, vec![(vec![1], 5), (vec![0], 5)]);
+    }
+
+    #[test]
+    fn check_iter_respects_ascending_order() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::with_order(Order::Ascending);
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        let pairs: Vec<(Vec<u8>, u64)> = queue.iter().collect();
+        assert_eq!(pairs, vec![(vec![2], 3), (vec![0], 5), (vec![1], 10)]);
+    }
+
+    #[test]
+    fn check_into_sorted_iter_matches_pop_order() {
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        let mut expected = Vec::new();
+        while let Some(element) = queue.pop() {
+            expected.push(element);
+        }
+
+        let mut queue: PriorityQueueImpl = PriorityQueueImpl::new();
+        queue.insert(vec![0], 5);
+        queue.insert(vec![1], 10);
+        queue.insert(vec![2], 3);
+
+        let actual: Vec<Vec<u8>> = queue.into_sorted_iter().map(|(element, _)| element).collect();
+        assert_eq!(actual, expected);
+    }
+
 }